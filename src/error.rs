@@ -1,142 +1,303 @@
 use std::fmt;
 
-use failure::{Backtrace, Context, Fail};
+use serde::{Deserialize, Serialize};
 
 /// Any error that can occur while using `celery`.
 #[derive(Debug)]
 pub struct Error {
-    inner: Context<ErrorKind>,
+    kind: ErrorKind,
 }
 
 /// Error kinds that can occur while using `celery`.
-#[derive(Debug, Fail)]
+#[non_exhaustive]
+#[derive(Debug)]
 pub enum ErrorKind {
     /// You tried to register a task but a task by that name already exists.
-    #[fail(display = "Task named '{}' already exists", _0)]
     TaskAlreadyExists(String),
 
     /// Received an unregistered task.
-    #[fail(display = "Received unregistered task named '{}'", _0)]
     UnregisteredTaskError(String),
 
     /// An AMQP broker error.
-    #[fail(display = "AMQP error: {:?}", _0)]
     AMQPError(Option<lapin::Error>),
 
     /// Raised when broker URL can't be parsed.
-    #[fail(display = "Broker URL is invalid: {}", _0)]
     InvalidBrokerUrl(String),
 
     /// An error occured while serializing or deserializing.
-    #[fail(display = "Serialization error: {}", _0)]
     SerializationError(serde_json::Error),
 
     /// A consumed delivery was in an unknown format.
-    #[fail(display = "Failed to parse message: ({})", _0)]
     AMQPMessageParseError(String),
 
     /// The queue you're attempting to use has not been defined.
-    #[fail(display = "Unknown queue '{}'", _0)]
     UnknownQueueError(String),
 
     /// An error that is expected to happen every once in a while and should trigger
     /// the task to be retried without causes a fit.
-    #[fail(display = "{}", _0)]
     ExpectedError(String),
 
     /// Should be used when a task encounters an error that is unexpected.
-    #[fail(display = "{}", _0)]
     UnexpectedError(String),
 
     /// Should be used when an expired task is received.
-    #[fail(display = "Task expired")]
     TaskExpiredError,
 
     /// Raise when a task should be retried.
-    #[fail(display = "Retrying task")]
     Retry,
 
     /// When a mutex is poisened, for example.
-    #[fail(display = "Sync error")]
     SyncError,
 
     /// An IO error.
-    #[fail(display = "An IO error occured ({:?})", _0)]
-    IoError(tokio::io::ErrorKind),
+    IoError(tokio::io::Error),
 
     /// Forced shutdown.
-    #[fail(display = "Forced shutdown")]
     ForcedShutdown,
 
     /// Task timed out.
-    #[fail(display = "Task timed out")]
     TimeoutError,
 
     /// Invalid routing glob pattern.
-    #[fail(display = "Bad routing rule pattern: {:?}", _0)]
     BadRoutingRulePatternError(Option<String>),
 
     /// Broker connection failed.
-    #[fail(display = "Broker connection error")]
     BrokerConnectionError,
+
+    /// A task-specific error that doesn't fit any of the other kinds. Carries the
+    /// original error so callers can still downcast back to its concrete type.
+    Unhandled(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+/// A stable, coarse-grained category for an [`ErrorKind`].
+///
+/// Unlike `Display`, which is meant for humans and can change wording freely,
+/// `ErrorCode` is meant for machines: dashboards, metrics labels, and
+/// dead-letter routing can match on it without parsing error strings.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Serialization,
+    Broker,
+    Routing,
+    Timeout,
+    NotFound,
+    Internal,
 }
 
-impl Fail for Error {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
+impl ErrorCode {
+    /// The stable string form of this code, suitable for metrics labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Serialization => "serialization",
+            ErrorCode::Broker => "broker",
+            ErrorCode::Routing => "routing",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Internal => "internal",
+        }
     }
+}
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-impl fmt::Display for Error {
+/// Strip `user:pass@` credentials from a broker URL before it ends up in an
+/// error message, since those messages get logged and, via `TaskFailure`,
+/// persisted into a result backend that other clients can read.
+fn redact_url_credentials(url: &str) -> String {
+    let scheme_end = match url.find("://") {
+        Some(i) => i,
+        None => return url.to_string(),
+    };
+    let authority_start = scheme_end + "://".len();
+    let authority_end = url[authority_start..]
+        .find('/')
+        .map_or(url.len(), |i| authority_start + i);
+
+    match url[authority_start..authority_end].rfind('@') {
+        Some(at) => format!(
+            "{}://***{}",
+            &url[..scheme_end],
+            &url[authority_start + at..]
+        ),
+        None => url.to_string(),
+    }
+}
+
+impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.inner, f)
+        match self {
+            ErrorKind::TaskAlreadyExists(name) => {
+                write!(f, "Task named '{}' already exists", name)
+            }
+            ErrorKind::UnregisteredTaskError(name) => {
+                write!(f, "Received unregistered task named '{}'", name)
+            }
+            ErrorKind::AMQPError(err) => write!(f, "AMQP error: {:?}", err),
+            ErrorKind::InvalidBrokerUrl(url) => {
+                write!(f, "Broker URL is invalid: {}", redact_url_credentials(url))
+            }
+            ErrorKind::SerializationError(err) => write!(f, "Serialization error: {}", err),
+            ErrorKind::AMQPMessageParseError(msg) => write!(f, "Failed to parse message: ({})", msg),
+            ErrorKind::UnknownQueueError(name) => write!(f, "Unknown queue '{}'", name),
+            ErrorKind::ExpectedError(msg) => write!(f, "{}", msg),
+            ErrorKind::UnexpectedError(msg) => write!(f, "{}", msg),
+            ErrorKind::TaskExpiredError => write!(f, "Task expired"),
+            ErrorKind::Retry => write!(f, "Retrying task"),
+            ErrorKind::SyncError => write!(f, "Sync error"),
+            ErrorKind::IoError(err) => write!(f, "An IO error occured ({:?})", err.kind()),
+            ErrorKind::ForcedShutdown => write!(f, "Forced shutdown"),
+            ErrorKind::TimeoutError => write!(f, "Task timed out"),
+            ErrorKind::BadRoutingRulePatternError(pattern) => {
+                write!(f, "Bad routing rule pattern: {:?}", pattern)
+            }
+            ErrorKind::BrokerConnectionError => write!(f, "Broker connection error"),
+            ErrorKind::Unhandled(err) => write!(f, "{}", err),
+        }
     }
 }
 
-impl Error {
-    /// Get the inner `ErrorKind`.
-    pub fn kind(&self) -> &ErrorKind {
-        self.inner.get_context()
+impl ErrorKind {
+    /// Whether a task that failed with this error is worth retrying.
+    ///
+    /// `true` means the failure is likely transient (a dropped connection, a
+    /// slow broker) and a retry has a reasonable chance of succeeding. `false`
+    /// means the failure is permanent (a bad routing pattern, a serialization
+    /// bug) and retrying would just fail the same way forever.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ErrorKind::BrokerConnectionError
+            | ErrorKind::TimeoutError
+            | ErrorKind::IoError(_)
+            | ErrorKind::ExpectedError(_)
+            | ErrorKind::AMQPError(_)
+            | ErrorKind::Retry => true,
+
+            ErrorKind::UnregisteredTaskError(_)
+            | ErrorKind::SerializationError(_)
+            | ErrorKind::BadRoutingRulePatternError(_)
+            | ErrorKind::UnknownQueueError(_)
+            | ErrorKind::TaskAlreadyExists(_)
+            | ErrorKind::InvalidBrokerUrl(_)
+            | ErrorKind::AMQPMessageParseError(_)
+            | ErrorKind::UnexpectedError(_)
+            | ErrorKind::TaskExpiredError
+            | ErrorKind::SyncError
+            | ErrorKind::ForcedShutdown
+            | ErrorKind::Unhandled(_) => false,
+        }
+    }
+
+    /// The stable [`ErrorCode`] for this kind, independent of its `Display` text.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ErrorKind::SerializationError(_) => ErrorCode::Serialization,
+
+            ErrorKind::AMQPError(_)
+            | ErrorKind::BrokerConnectionError
+            | ErrorKind::InvalidBrokerUrl(_) => ErrorCode::Broker,
+
+            ErrorKind::BadRoutingRulePatternError(_) => ErrorCode::Routing,
+
+            ErrorKind::TimeoutError => ErrorCode::Timeout,
+
+            ErrorKind::UnregisteredTaskError(_) | ErrorKind::UnknownQueueError(_) => {
+                ErrorCode::NotFound
+            }
+
+            ErrorKind::TaskAlreadyExists(_)
+            | ErrorKind::AMQPMessageParseError(_)
+            | ErrorKind::ExpectedError(_)
+            | ErrorKind::UnexpectedError(_)
+            | ErrorKind::TaskExpiredError
+            | ErrorKind::Retry
+            | ErrorKind::SyncError
+            | ErrorKind::IoError(_)
+            | ErrorKind::ForcedShutdown
+            | ErrorKind::Unhandled(_) => ErrorCode::Internal,
+        }
     }
 }
 
-impl From<ErrorKind> for Error {
-    fn from(kind: ErrorKind) -> Error {
-        Error {
-            inner: Context::new(kind),
+impl std::error::Error for ErrorKind {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ErrorKind::AMQPError(Some(err)) => Some(err),
+            ErrorKind::SerializationError(err) => Some(err),
+            ErrorKind::IoError(err) => Some(err),
+            ErrorKind::Unhandled(err) => Some(err.as_ref()),
+            _ => None,
         }
     }
 }
 
-impl From<Context<ErrorKind>> for Error {
-    fn from(inner: Context<ErrorKind>) -> Error {
-        Error { inner }
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.kind, f)
     }
 }
 
-impl From<Context<&str>> for Error {
-    fn from(inner: Context<&str>) -> Error {
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.kind.source()
+    }
+}
+
+impl Error {
+    /// Get the inner `ErrorKind`.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Whether the task that produced this error is worth retrying. See
+    /// [`ErrorKind::is_transient`].
+    pub fn is_transient(&self) -> bool {
+        self.kind.is_transient()
+    }
+
+    /// The stable [`ErrorCode`] for this error. See [`ErrorKind::code`].
+    pub fn code(&self) -> ErrorCode {
+        self.kind.code()
+    }
+
+    /// Wrap an arbitrary, task-specific error as an [`ErrorKind::Unhandled`].
+    ///
+    /// Use this from task bodies to propagate a domain error through `celery`
+    /// without flattening it into a string first; the original error can be
+    /// recovered again with `err.kind()` and a downcast on the boxed value.
+    ///
+    /// There's no blanket `From<E>` for this because it would overlap with the
+    /// specific `From` impls below (`lapin::Error`, `serde_json::Error`, ...), so
+    /// call this explicitly instead.
+    pub fn unhandled<E>(err: E) -> Error
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
         Error {
-            inner: Context::new(ErrorKind::UnexpectedError(
-                (*inner.get_context()).to_string(),
-            )),
+            kind: ErrorKind::Unhandled(Box::new(err)),
         }
     }
 }
 
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error { kind }
+    }
+}
+
 impl From<lapin::Error> for Error {
     fn from(err: lapin::Error) -> Error {
         Error {
-            inner: Context::new(match err {
+            kind: match err {
                 lapin::Error::NotConnected => ErrorKind::BrokerConnectionError,
                 lapin::Error::ConnectionRefused => ErrorKind::BrokerConnectionError,
                 lapin::Error::IOError(_) => ErrorKind::BrokerConnectionError,
                 _ => ErrorKind::AMQPError(Some(err)),
-            }),
+            },
         }
     }
 }
@@ -144,7 +305,7 @@ impl From<lapin::Error> for Error {
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Error {
         Error {
-            inner: Context::new(ErrorKind::SerializationError(err)),
+            kind: ErrorKind::SerializationError(err),
         }
     }
 }
@@ -152,7 +313,7 @@ impl From<serde_json::Error> for Error {
 impl From<tokio::io::Error> for Error {
     fn from(err: tokio::io::Error) -> Error {
         Error {
-            inner: Context::new(ErrorKind::IoError(err.kind())),
+            kind: ErrorKind::IoError(err),
         }
     }
 }
@@ -160,7 +321,7 @@ impl From<tokio::io::Error> for Error {
 impl From<tokio::time::Elapsed> for Error {
     fn from(_err: tokio::time::Elapsed) -> Error {
         Error {
-            inner: Context::new(ErrorKind::TimeoutError),
+            kind: ErrorKind::TimeoutError,
         }
     }
 }
@@ -168,9 +329,56 @@ impl From<tokio::time::Elapsed> for Error {
 impl From<globset::Error> for Error {
     fn from(_err: globset::Error) -> Error {
         Error {
-            inner: Context::new(ErrorKind::BadRoutingRulePatternError(
-                _err.glob().map(|s| s.into()),
-            )),
+            kind: ErrorKind::BadRoutingRulePatternError(_err.glob().map(|s| s.into())),
         }
     }
 }
+
+/// A serializable record of a task failure, suitable for persisting into a
+/// result backend so clients can inspect why a task failed.
+///
+/// This is the Celery-compatible, language-agnostic counterpart to [`Error`]:
+/// where `Error` is a Rust type for propagating failures within this process,
+/// `TaskFailure` is a JSON shape for handing the failure reason to anyone
+/// reading the result backend, regardless of what they wrote their consumer in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskFailure {
+    /// The stable [`ErrorCode::as_str`] of the error that failed the task.
+    pub code: String,
+
+    /// The human-readable `Display` message of the error.
+    pub message: String,
+
+    /// How many times the task had already been retried when it failed.
+    ///
+    /// `Error` doesn't track attempt counts itself, so conversions from it
+    /// default this to `0`; the task runner should overwrite it with the
+    /// actual retry count before persisting.
+    pub retries: u32,
+
+    /// An optional traceback or backtrace, if one was captured.
+    pub traceback: Option<String>,
+}
+
+impl From<&Error> for TaskFailure {
+    fn from(err: &Error) -> TaskFailure {
+        TaskFailure::from(err.kind())
+    }
+}
+
+impl From<&ErrorKind> for TaskFailure {
+    fn from(kind: &ErrorKind) -> TaskFailure {
+        TaskFailure {
+            code: kind.code().as_str().to_string(),
+            message: kind.to_string(),
+            retries: 0,
+            traceback: None,
+        }
+    }
+}
+
+impl From<ErrorKind> for TaskFailure {
+    fn from(kind: ErrorKind) -> TaskFailure {
+        TaskFailure::from(&kind)
+    }
+}